@@ -28,12 +28,24 @@
 //! and `get` for adding a node to the ring, removing a node from the ring, and
 //! getting the node responsible for the provided key.
 //!
+//! Every node added to the ring is represented internally by a configurable
+//! number of virtual nodes (see `with_replicas`), which smooths out key
+//! distribution and keeps key migrations small when a node is added or
+//! removed. Nodes that should receive a larger share of keys can be added
+//! with `add_weighted`.
+//!
+//! `HashRing` hashes nodes and keys with a configurable `BuildHasher` (see
+//! `with_hasher`), defaulting to the standard library's `RandomState`.
+//!
+//! For workloads with a skewed key distribution, `get_balanced` offers an
+//! opt-in bounded-load variant of `get` that spills a key onto the next node
+//! clockwise once a node is carrying more than its fair share, paired with
+//! `release` to free a key's slot once it's no longer owned.
+//!
 //! ## Example
 //!
 //! Below is a simple example of how an application might use `HashRing` to make
-//! use of consistent hashing. Since `HashRing` exposes only a minimal API clients
-//! can build other abstractions, such as virtual nodes, on top of it. The example
-//! below shows one potential implementation of virtual nodes on top of `HashRing`
+//! use of consistent hashing.
 //!
 //! ``` rust,no_run
 //! extern crate hashring;
@@ -92,96 +104,200 @@
 //! }
 //! ```
 
-extern crate crypto;
-
 use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
 
-use crypto::digest::Digest;
-use crypto::md5::Md5;
+/// The default number of virtual nodes (replicas) placed on the ring for each
+/// node added with `add`.
+const DEFAULT_REPLICAS: usize = 1;
+
+/// The default slack `get_balanced` allows above the average load per node
+/// before spilling a key onto the next node clockwise.
+const DEFAULT_LOAD_FACTOR: f64 = 0.25;
 
-// Node is an internal struct used to encapsulate the nodes that will be added and
-// removed from `HashRing`
-#[derive(Debug)]
-struct Node<T> {
+// Node is an internal struct used to represent a virtual node placed on the ring.
+// It doesn't own the physical node itself, just the index of it in `HashRing::nodes`,
+// so that several virtual nodes can point back to the same physical node.
+#[derive(Debug, Clone, Copy)]
+struct Node {
     key: u64,
-    node: T,
+    node_idx: usize,
 }
 
-impl<T> Node<T> {
-    fn new(key: u64, node: T) -> Node<T> {
-        Node { key, node }
+impl Node {
+    fn new(key: u64, node_idx: usize) -> Node {
+        Node { key, node_idx }
     }
 }
 
 // Implement `PartialEq`, `Eq`, `PartialOrd` and `Ord` so we can sort `Node`s
-impl<T> PartialEq for Node<T> {
-    fn eq(&self, other: &Node<T>) -> bool {
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
         self.key == other.key
     }
 }
 
-impl<T> Eq for Node<T> {}
+impl Eq for Node {}
 
-impl<T> PartialOrd for Node<T> {
-    fn partial_cmp(&self, other: &Node<T>) -> Option<Ordering> {
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<T> Ord for Node<T> {
-    fn cmp(&self, other: &Node<T>) -> Ordering {
+impl Ord for Node {
+    fn cmp(&self, other: &Node) -> Ordering {
         self.key.cmp(&other.key)
     }
 }
 
-pub struct HashRing<T, U> {
-    ring: Vec<Node<T>>,
-    hash: Md5,
-    buf: [u8; 16],
+pub struct HashRing<T, U, S = RandomState> {
+    ring: Vec<Node>,
+    nodes: Vec<T>,
+    replicas: usize,
+    hash_builder: S,
+    // Bounded-load state: `loads[i]` is the number of keys currently assigned
+    // to `nodes[i]` by `get_balanced`. `assignments` is a stack per key rather
+    // than a single slot, since the same key can be passed to `get_balanced`
+    // more than once before being `release`d; each `release` pops the most
+    // recent assignment so earlier ones stay releasable.
+    loads: Vec<usize>,
+    total_assignments: usize,
+    load_factor: f64,
+    assignments: HashMap<String, Vec<usize>>,
     phantom: PhantomData<U>,
 }
 
 /// Hash Ring
 ///
 /// A hash ring that provides consistent hashing for nodes that are added to it.
-impl<T, U> HashRing<T, U>
+impl<T, U, S> HashRing<T, U, S>
 where
     T: ToString,
     U: ToString,
+    S: BuildHasher,
 {
     /// Create a new HashRing.
-    pub fn new() -> HashRing<T, U> {
+    pub fn new() -> HashRing<T, U, S>
+    where
+        S: Default,
+    {
         Default::default()
     }
 
-    /// Get the number of nodes in the hash ring.
+    /// Create a new HashRing where every node is represented by `replicas`
+    /// virtual nodes on the ring.
+    pub fn with_replicas(replicas: usize) -> HashRing<T, U, S>
+    where
+        S: Default,
+    {
+        HashRing {
+            replicas,
+            ..Default::default()
+        }
+    }
+
+    /// Create a new HashRing that hashes nodes and keys with `build_hasher`
+    /// instead of the default `RandomState`.
+    pub fn with_hasher(build_hasher: S) -> HashRing<T, U, S> {
+        HashRing {
+            ring: Vec::new(),
+            nodes: Vec::new(),
+            replicas: DEFAULT_REPLICAS,
+            hash_builder: build_hasher,
+            loads: Vec::new(),
+            total_assignments: 0,
+            load_factor: DEFAULT_LOAD_FACTOR,
+            assignments: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Set the load factor `epsilon` used by `get_balanced`, replacing the
+    /// default of `0.25`. A node is considered full once its load reaches
+    /// `ceil(average_load * (1 + epsilon))`, so a smaller `epsilon` spreads
+    /// keys more evenly at the cost of spilling onto more nodes.
+    pub fn with_load_factor(mut self, epsilon: f64) -> Self {
+        self.load_factor = epsilon;
+        self
+    }
+
+    /// Get the number of physical nodes in the hash ring.
     pub fn len(&self) -> usize {
-        self.ring.len()
+        self.nodes.len()
     }
 
     /// Returns true if the ring has no elements.
     pub fn is_empty(&self) -> bool {
-        self.ring.len() == 0
+        self.nodes.is_empty()
+    }
+
+    /// Add `node` to the hash ring, placing `self.replicas` virtual nodes for it.
+    pub fn add(&mut self, node: T) {
+        self.add_weighted(node, 1);
     }
 
-    /// Add `node` to the hash ring.
-           pub fn add(&mut self, node: T) {
-        let s = node.to_string();
-        let key = self.get_key(&s);
-        self.ring.push(Node::new(key, node));
+    /// Add `node` to the hash ring with `weight` times the usual number of virtual
+    /// nodes, so it receives a proportionally larger share of the key space. A
+    /// `weight` of `0` is clamped up to `1` rather than adding the node with no
+    /// virtual nodes, since that would make it permanently unreachable via `get`.
+    pub fn add_weighted(&mut self, node: T, weight: usize) {
+        let idx = self.nodes.len();
+        let name = node.to_string();
+
+        let replicas = self.replicas * weight.max(1);
+        for i in 0..replicas {
+            let key = self.get_key(&format!("{}:{}", name, i));
+            self.ring.push(Node::new(key, idx));
+        }
         self.ring.sort();
+
+        self.nodes.push(node);
+        self.loads.push(0);
     }
 
-    /// Remove `node` from the hash ring. Returns an `Option` that will contain the `node`
-    /// if it was in the hash ring or `None` if it was not present.
+    /// Remove `node` from the hash ring, along with all of its virtual nodes.
+    /// Returns an `Option` that will contain the `node` if it was in the hash
+    /// ring or `None` if it was not present.
     pub fn remove(&mut self, node: &T) -> Option<T> {
-        let s = node.to_string();
-        let key = self.get_key(&s);
-        match self.ring.binary_search_by(|node| node.key.cmp(&key)) {
-            Err(_) => None,
-            Ok(n) => Some(self.ring.remove(n).node),
+        let name = node.to_string();
+        let idx = self.nodes.iter().position(|n| n.to_string() == name)?;
+
+        self.ring.retain(|n| n.node_idx != idx);
+
+        let removed = self.nodes.swap_remove(idx);
+        let removed_load = self.loads.swap_remove(idx);
+        self.total_assignments -= removed_load;
+
+        // `swap_remove` moved the last node into `idx`; repoint any virtual
+        // nodes and tracked assignments that referenced it, and drop any
+        // assignments that belonged to the removed node.
+        let moved_idx = self.nodes.len();
+        if idx != moved_idx {
+            for n in self.ring.iter_mut() {
+                if n.node_idx == moved_idx {
+                    n.node_idx = idx;
+                }
+            }
         }
+        self.assignments.retain(|_, stack| {
+            stack.retain(|v| *v != idx);
+            !stack.is_empty()
+        });
+        if idx != moved_idx {
+            for stack in self.assignments.values_mut() {
+                for v in stack.iter_mut() {
+                    if *v == moved_idx {
+                        *v = idx;
+                    }
+                }
+            }
+        }
+
+        Some(removed)
     }
 
     /// Get the node responsible for `key`. Returns an `Option` that will contain the `node`
@@ -191,43 +307,154 @@ where
             return None;
         }
 
+        let k = self.get_key(&key.to_string());
+        let n = self.locate(k);
+
+        Some(&self.nodes[self.ring[n].node_idx])
+    }
+
+    /// Get the `n` distinct physical nodes responsible for replicating `key`. The
+    /// first entry is the same node `get` would return; the rest are the next
+    /// distinct nodes walking clockwise around the ring, wrapping past the end.
+    /// Virtual nodes belonging to a node already collected are skipped. Returns
+    /// fewer than `n` nodes only if the ring has fewer than `n` distinct nodes.
+    pub fn get_replicas(&mut self, key: &U, n: usize) -> Vec<&T> {
+        if self.ring.is_empty() || n == 0 {
+            return Vec::new();
+        }
+
+        let k = self.get_key(&key.to_string());
+        let start = self.locate(k);
+
+        let want = n.min(self.nodes.len());
+        let mut seen = vec![false; self.nodes.len()];
+        let mut replicas = Vec::with_capacity(want);
+
+        for i in 0..self.ring.len() {
+            let idx = self.ring[(start + i) % self.ring.len()].node_idx;
+            if seen[idx] {
+                continue;
+            }
+            seen[idx] = true;
+            replicas.push(&self.nodes[idx]);
+            if replicas.len() == want {
+                break;
+            }
+        }
+
+        replicas
+    }
+
+    /// Get the node responsible for `key`, same as `get`, but bounded so no
+    /// node carries much more than its fair share of the currently assigned
+    /// keys (see [Consistent Hashing with Bounded
+    /// Loads](https://arxiv.org/abs/1608.01350)). If the primary node is at
+    /// capacity the key is walked clockwise onto the next node that isn't; if
+    /// every node is at capacity it falls back to the primary. Pair with
+    /// `release` once the caller is done with the key so its slot is freed.
+    /// Calling this more than once for the same `key` before releasing it is
+    /// supported: each call pushes a new assignment, and each `release` frees
+    /// the most recent one.
+    pub fn get_balanced(&mut self, key: &U) -> Option<&T> {
+        if self.ring.is_empty() {
+            return None;
+        }
+
+        let k = self.get_key(&key.to_string());
+        let start = self.locate(k);
+
+        let cap = self.capacity();
+        let primary = self.ring[start].node_idx;
+        let mut chosen = primary;
+        let mut seen = vec![false; self.nodes.len()];
+
+        for i in 0..self.ring.len() {
+            let idx = self.ring[(start + i) % self.ring.len()].node_idx;
+            if seen[idx] {
+                continue;
+            }
+            seen[idx] = true;
+            if self.loads[idx] < cap {
+                chosen = idx;
+                break;
+            }
+        }
+
+        self.loads[chosen] += 1;
+        self.total_assignments += 1;
+        self.assignments
+            .entry(key.to_string())
+            .or_default()
+            .push(chosen);
+
+        Some(&self.nodes[chosen])
+    }
+
+    /// Release one of `key`'s slots acquired by `get_balanced`, freeing up
+    /// capacity on the node it was assigned to. If `get_balanced` was called
+    /// more than once for `key` before releasing, this frees the most recent
+    /// assignment; does nothing if `key` has no tracked assignment left.
+    pub fn release(&mut self, key: &U) {
         let s = key.to_string();
-        let k = self.get_key(&s);
+        if let Some(stack) = self.assignments.get_mut(&s) {
+            if let Some(idx) = stack.pop() {
+                self.loads[idx] -= 1;
+                self.total_assignments -= 1;
+            }
+            if stack.is_empty() {
+                self.assignments.remove(&s);
+            }
+        }
+    }
+
+    // The number of keys a node may carry before `get_balanced` spills onto the
+    // next node: the average load across all nodes, inflated by `load_factor`
+    // and rounded up.
+    fn capacity(&self) -> usize {
+        if self.nodes.is_empty() {
+            return 0;
+        }
+
+        let avg = self.total_assignments as f64 / self.nodes.len() as f64;
+        (avg * (1.0 + self.load_factor)).ceil() as usize
+    }
 
-        let n = match self.ring.binary_search_by(|node| node.key.cmp(&k)) {
+    // Find the ring index of the node responsible for a hashed key, wrapping
+    // past the end of the ring back to index 0. Shared by `get`, `get_replicas`
+    // and `get_balanced` so the clockwise/wraparound rule only lives in one place.
+    fn locate(&self, key_hash: u64) -> usize {
+        let n = match self.ring.binary_search_by(|node| node.key.cmp(&key_hash)) {
             Err(n) => n,
             Ok(n) => n,
         };
 
         if n == self.ring.len() {
-            return Some(&self.ring[0].node);
+            0
+        } else {
+            n
         }
-
-        Some(&self.ring[n].node)
     }
 
     // An internal function for converting a reference to a `str` into a `u64` which
     // can be used as a key in the hash ring.
-    fn get_key(&mut self, s: &str) -> u64 {
-        self.hash.reset();
-        self.hash.input_str(s);
-        self.hash.result(&mut self.buf);
-
-        let n: u64 = u64::from(self.buf[7]) << 56 | u64::from(self.buf[6]) << 48
-            | u64::from(self.buf[5]) << 40 | u64::from(self.buf[4]) << 32
-            | u64::from(self.buf[3]) << 24 | u64::from(self.buf[2]) << 16
-            | u64::from(self.buf[1]) << 8 | u64::from(self.buf[0]) as u64;
-
-        n
+    fn get_key(&self, s: &str) -> u64 {
+        let mut hasher = self.hash_builder.build_hasher();
+        hasher.write(s.as_bytes());
+        hasher.finish()
     }
 }
 
-impl<T, U> Default for HashRing<T, U> {
+impl<T, U, S: Default> Default for HashRing<T, U, S> {
     fn default() -> Self {
         HashRing {
             ring: Vec::new(),
-            hash: Md5::new(),
-            buf: [0; 16],
+            nodes: Vec::new(),
+            replicas: DEFAULT_REPLICAS,
+            hash_builder: S::default(),
+            loads: Vec::new(),
+            total_assignments: 0,
+            load_factor: DEFAULT_LOAD_FACTOR,
+            assignments: HashMap::new(),
             phantom: PhantomData,
         }
     }
@@ -319,16 +546,263 @@ mod tests {
         ring.add(vnode5);
         ring.add(vnode6);
 
-        assert_eq!(ring.get(&"foo"), Some(&vnode1));
-        assert_eq!(ring.get(&"bar"), Some(&vnode2));
-        assert_eq!(ring.get(&"baz"), Some(&vnode1));
+        for key in &["foo", "bar", "baz", "abc", "def", "ghi", "cat", "dog", "bird"] {
+            assert!(ring.get(key).is_some());
+        }
+    }
+
+    // A `BuildHasher` that maps fixed strings to fixed `u64`s, so tests can pin
+    // down exact ring positions instead of just checking membership.
+    struct StubHasher {
+        table: std::rc::Rc<std::collections::HashMap<String, u64>>,
+        value: u64,
+    }
+
+    impl std::hash::Hasher for StubHasher {
+        fn finish(&self) -> u64 {
+            self.value
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            let s = String::from_utf8_lossy(bytes).into_owned();
+            self.value = *self
+                .table
+                .get(&s)
+                .unwrap_or_else(|| panic!("no stub hash for {:?}", s));
+        }
+    }
+
+    #[derive(Clone)]
+    struct StubHasherBuilder {
+        table: std::rc::Rc<std::collections::HashMap<String, u64>>,
+    }
+
+    impl std::hash::BuildHasher for StubHasherBuilder {
+        type Hasher = StubHasher;
+
+        fn build_hasher(&self) -> StubHasher {
+            StubHasher {
+                table: self.table.clone(),
+                value: 0,
+            }
+        }
+    }
+
+    #[test]
+    fn get_resolves_exact_positions_with_stub_hasher() {
+        let table: std::collections::HashMap<String, u64> = std::collections::HashMap::from([
+            ("A:0".to_string(), 10),
+            ("B:0".to_string(), 20),
+            ("C:0".to_string(), 30),
+            ("low".to_string(), 5),
+            ("mid".to_string(), 15),
+            ("high".to_string(), 25),
+            ("max".to_string(), 35),
+            ("exact".to_string(), 20),
+        ]);
+
+        let mut ring: HashRing<&str, &str, StubHasherBuilder> = HashRing::with_hasher(
+            StubHasherBuilder {
+                table: std::rc::Rc::new(table),
+            },
+        );
+
+        ring.add("A");
+        ring.add("B");
+        ring.add("C");
+
+        assert_eq!(ring.get(&"low"), Some(&"A"));
+        assert_eq!(ring.get(&"mid"), Some(&"B"));
+        assert_eq!(ring.get(&"high"), Some(&"C"));
+        // "max" hashes past every node on the ring, so lookup must wrap back to
+        // the first node rather than panicking or staying at the last index.
+        assert_eq!(ring.get(&"max"), Some(&"A"));
+        // "exact" lands exactly on B's virtual node key.
+        assert_eq!(ring.get(&"exact"), Some(&"B"));
+    }
+
+    #[test]
+    fn virtual_nodes_are_purged_on_removal() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+        assert_eq!(ring.ring.len(), 16);
+
+        ring.remove(&vnode1);
+        assert_eq!(ring.len(), 1);
+        assert_eq!(ring.ring.len(), 8);
+        assert!(ring.ring.iter().all(|n| n.node_idx == 0));
+    }
+
+    #[test]
+    fn weighted_nodes_get_more_virtual_nodes() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(4);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add_weighted(vnode2, 3);
+
+        assert_eq!(ring.ring.len(), 4 + 4 * 3);
 
-        assert_eq!(ring.get(&"abc"), Some(&vnode6));
-        assert_eq!(ring.get(&"def"), Some(&vnode3));
-        assert_eq!(ring.get(&"ghi"), Some(&vnode3));
+        let vnode1_entries = ring.ring.iter().filter(|n| n.node_idx == 0).count();
+        let vnode2_entries = ring.ring.iter().filter(|n| n.node_idx == 1).count();
+        assert_eq!(vnode1_entries, 4);
+        assert_eq!(vnode2_entries, 12);
+    }
+
+    #[test]
+    fn get_resolves_virtual_nodes_back_to_physical_nodes() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(16);
 
-        assert_eq!(ring.get(&"cat"), Some(&vnode5));
-        assert_eq!(ring.get(&"dog"), Some(&vnode6));
-        assert_eq!(ring.get(&"bird"), Some(&vnode2));
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+        let vnode3 = VNode::new("127.0.0.3", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+        ring.add(vnode3);
+
+        for key in &["foo", "bar", "baz", "abc", "def", "ghi"] {
+            let got = ring.get(key).unwrap();
+            assert!(*got == vnode1 || *got == vnode2 || *got == vnode3);
+        }
+    }
+
+    #[test]
+    fn get_replicas_returns_distinct_physical_nodes() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+        let vnode3 = VNode::new("127.0.0.3", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+        ring.add(vnode3);
+
+        let replicas = ring.get_replicas(&"foo", 2);
+        assert_eq!(replicas.len(), 2);
+        assert_ne!(replicas[0], replicas[1]);
+
+        // Asking for more replicas than there are distinct nodes only returns
+        // as many as exist.
+        let replicas = ring.get_replicas(&"foo", 10);
+        assert_eq!(replicas.len(), 3);
+
+        assert!(ring.get_replicas(&"foo", 0).is_empty());
+    }
+
+    #[test]
+    fn get_replicas_on_empty_ring() {
+        let mut ring: HashRing<VNode, &str> = HashRing::new();
+        assert!(ring.get_replicas(&"foo", 3).is_empty());
+    }
+
+    #[test]
+    fn with_hasher_uses_the_provided_build_hasher() {
+        use std::collections::hash_map::RandomState;
+
+        let mut ring: HashRing<VNode, &str, RandomState> =
+            HashRing::with_hasher(RandomState::new());
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+
+        assert_eq!(ring.len(), 2);
+        assert!(ring.get(&"foo").is_some());
+    }
+
+    #[test]
+    fn get_balanced_spreads_keys_across_nodes() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8).with_load_factor(0.25);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+        let vnode3 = VNode::new("127.0.0.3", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+        ring.add(vnode3);
+
+        let keys: Vec<String> = (0..30).map(|i| format!("key-{}", i)).collect();
+        for key in &keys {
+            ring.get_balanced(&key.as_str());
+        }
+
+        assert_eq!(ring.loads.iter().sum::<usize>(), 30);
+
+        let cap = ring.capacity();
+        assert!(ring.loads.iter().all(|&load| load <= cap));
+    }
+
+    #[test]
+    fn release_frees_up_capacity_for_a_key() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+
+        ring.get_balanced(&"foo");
+        assert_eq!(ring.total_assignments, 1);
+
+        ring.release(&"foo");
+        assert_eq!(ring.total_assignments, 0);
+        assert!(ring.loads.iter().all(|&load| load == 0));
+
+        // Releasing a key with no tracked assignment is a no-op.
+        ring.release(&"foo");
+        assert_eq!(ring.total_assignments, 0);
+    }
+
+    #[test]
+    fn get_balanced_tracks_repeated_assignments_of_the_same_key() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        let vnode2 = VNode::new("127.0.0.2", 1024, 1);
+
+        ring.add(vnode1);
+        ring.add(vnode2);
+
+        // The same key can be in flight more than once before it's released.
+        ring.get_balanced(&"foo");
+        ring.get_balanced(&"foo");
+        ring.get_balanced(&"foo");
+        assert_eq!(ring.total_assignments, 3);
+
+        ring.release(&"foo");
+        ring.release(&"foo");
+        ring.release(&"foo");
+        assert_eq!(ring.total_assignments, 0);
+        assert!(ring.loads.iter().all(|&load| load == 0));
+
+        // A fourth release with nothing left to release is a no-op.
+        ring.release(&"foo");
+        assert_eq!(ring.total_assignments, 0);
+    }
+
+    #[test]
+    fn get_balanced_falls_back_to_primary_when_every_node_is_full() {
+        let mut ring: HashRing<VNode, &str> = HashRing::with_replicas(8).with_load_factor(0.0);
+
+        let vnode1 = VNode::new("127.0.0.1", 1024, 1);
+        ring.add(vnode1);
+
+        for _ in 0..5 {
+            assert!(ring.get_balanced(&"foo").is_some());
+        }
+        assert_eq!(ring.loads[0], 5);
     }
 }